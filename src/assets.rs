@@ -0,0 +1,228 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use content_encoding::{negotiate_encoding, Negotiation};
+use Request;
+use Response;
+
+/// Serves a static file from the `path` directory, using the request's URL to determine which
+/// file to load.
+///
+/// Any query string is ignored, and any path that tries to escape `path` (for example through a
+/// `..` component) results in a 404 response rather than reading outside the directory.
+///
+/// If the request's `Accept-Encoding` header accepts a compressed encoding and a pre-compressed
+/// sidecar file exists next to the requested file (for example `style.css.br` or `style.css.gz`
+/// alongside `style.css`), that sidecar is served directly. The response then carries a
+/// `Content-Encoding` header and the `Content-Type` of the *original* file, so
+/// `content_encoding::apply` sees the header is already set and leaves the body alone instead of
+/// compressing it a second time.
+///
+/// # Example
+///
+/// ```rust
+/// use rouille::Request;
+/// use rouille::Response;
+/// use rouille::match_assets;
+///
+/// fn handle(request: &Request) -> Response {
+///     let response = match_assets(request, "public/");
+///     if response.is_success() {
+///         return response;
+///     }
+///
+///     Response::html("404 not found")
+/// }
+/// ```
+pub fn match_assets(request: &Request, path: &str) -> Response {
+    let file_path = match resolve_path(path, request.url()) {
+        Some(p) => p,
+        None => return Response::empty_404(),
+    };
+
+    let accept_encoding = request.header("Accept-Encoding").unwrap_or_else(String::new);
+
+    if let Some((coding, body)) = resolve_sidecar(&file_path, &accept_encoding) {
+        return Response::from_data(guess_content_type(&file_path), body)
+                   .with_additional_header("Content-Encoding", coding);
+    }
+
+    match read_file(&file_path) {
+        Some(body) => Response::from_data(guess_content_type(&file_path), body),
+        None => Response::empty_404(),
+    }
+}
+
+// Sidecar file extension paired with the `Content-Encoding` value it represents, in
+// server-preference order.
+const SIDECAR_CODINGS: [(&'static str, &'static str); 2] = [("br", "br"), ("gz", "gzip")];
+
+// Picks the best pre-compressed sidecar for `file_path` that the client accepts (via the same
+// `negotiate_encoding` helper `content_encoding::apply` uses) and that actually exists on disk,
+// and reads its contents.
+fn resolve_sidecar(file_path: &Path, accept_encoding: &str) -> Option<(&'static str, Vec<u8>)> {
+    let existing: Vec<&str> = SIDECAR_CODINGS.iter()
+        .filter(|&&(extension, _)| sidecar_path(file_path, extension).is_file())
+        .map(|&(_, coding)| coding)
+        .collect();
+
+    let coding = match negotiate_encoding(accept_encoding, &existing) {
+        Negotiation::Coding(coding) => coding,
+        Negotiation::Identity | Negotiation::NotAcceptable => return None,
+    };
+
+    let extension = SIDECAR_CODINGS.iter()
+                                   .find(|&&(_, c)| c == coding)
+                                   .map(|&(extension, _)| extension)
+                                   .unwrap();
+
+    read_file(&sidecar_path(file_path, extension)).map(|body| (coding, body))
+}
+
+// Turns a request URL into a path under `root`, rejecting anything that could escape it.
+fn resolve_path(root: &str, url: &str) -> Option<PathBuf> {
+    let url = url.splitn(2, '?').next().unwrap_or(url);
+
+    let mut file_path = Path::new(root).to_path_buf();
+    for component in Path::new(url).components() {
+        match component {
+            Component::Normal(part) => file_path.push(part),
+            Component::RootDir | Component::CurDir => (),
+            // `..`, or a prefix/root on another drive, could escape the asset directory.
+            _ => return None,
+        }
+    }
+
+    if file_path.is_dir() {
+        file_path.push("index.html");
+    }
+
+    Some(file_path)
+}
+
+// Builds `path.<extension>`, e.g. `style.css` + `br` => `style.css.br`.
+fn sidecar_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(extension);
+    PathBuf::from(sidecar)
+}
+
+fn read_file(path: &Path) -> Option<Vec<u8>> {
+    let mut file = File::open(path).ok()?;
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).ok()?;
+    Some(content)
+}
+
+// Guesses a `Content-Type` from a file's extension. This is a best-effort heuristic, the same as
+// `content_encoding::response_is_text`, and it's not a problem if it's sometimes wrong.
+fn guess_content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") | Some("htm") => "text/html; charset=utf8",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_path, resolve_sidecar};
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    // Each test gets its own directory under the system temp dir so they don't clash.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("rouille-assets-test-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn resolve_path_rejects_parent_traversal() {
+        let root = test_dir("traversal");
+        let root = root.to_str().unwrap();
+
+        assert!(resolve_path(root, "/../../etc/passwd").is_none());
+        assert!(resolve_path(root, "/foo/../../bar").is_none());
+    }
+
+    #[test]
+    fn resolve_path_strips_query_string_and_joins_root() {
+        let root = test_dir("query-string");
+        let root_str = root.to_str().unwrap();
+
+        let resolved = resolve_path(root_str, "/style.css?v=2").unwrap();
+        assert_eq!(resolved, root.join("style.css"));
+    }
+
+    #[test]
+    fn resolve_sidecar_prefers_br_over_gzip_when_both_exist_and_acceptable() {
+        let dir = test_dir("prefer-br");
+        let file = dir.join("style.css");
+        File::create(&file).unwrap().write_all(b"body {}").unwrap();
+        File::create(file.with_file_name("style.css.br")).unwrap().write_all(b"br-body").unwrap();
+        File::create(file.with_file_name("style.css.gz")).unwrap().write_all(b"gz-body").unwrap();
+
+        let (coding, body) = resolve_sidecar(&file, "br, gzip").unwrap();
+        assert_eq!(coding, "br");
+        assert_eq!(body, b"br-body");
+    }
+
+    #[test]
+    fn resolve_sidecar_falls_back_to_gzip_when_br_sidecar_is_missing() {
+        let dir = test_dir("fallback-gzip");
+        let file = dir.join("style.css");
+        File::create(&file).unwrap().write_all(b"body {}").unwrap();
+        File::create(file.with_file_name("style.css.gz")).unwrap().write_all(b"gz-body").unwrap();
+
+        let (coding, body) = resolve_sidecar(&file, "br, gzip").unwrap();
+        assert_eq!(coding, "gzip");
+        assert_eq!(body, b"gz-body");
+    }
+
+    #[test]
+    fn resolve_sidecar_honors_explicit_rejection() {
+        let dir = test_dir("forbidden-br");
+        let file = dir.join("style.css");
+        File::create(&file).unwrap().write_all(b"body {}").unwrap();
+        File::create(file.with_file_name("style.css.br")).unwrap().write_all(b"br-body").unwrap();
+        File::create(file.with_file_name("style.css.gz")).unwrap().write_all(b"gz-body").unwrap();
+
+        let (coding, body) = resolve_sidecar(&file, "br;q=0, gzip").unwrap();
+        assert_eq!(coding, "gzip");
+        assert_eq!(body, b"gz-body");
+    }
+
+    #[test]
+    fn resolve_sidecar_returns_none_without_a_matching_sidecar() {
+        let dir = test_dir("no-sidecar");
+        let file = dir.join("style.css");
+        File::create(&file).unwrap().write_all(b"body {}").unwrap();
+
+        assert!(resolve_sidecar(&file, "br, gzip").is_none());
+    }
+}
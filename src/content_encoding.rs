@@ -8,7 +8,6 @@
 // according to those terms.
 
 use std::str;
-use input;
 use Request;
 use Response;
 
@@ -23,7 +22,17 @@ use Response;
 /// no-op.
 ///
 /// The gzip encoding is supported only if you enable the `gzip` feature of rouille (which is
-/// enabled by default).
+/// enabled by default). The zstd and deflate encodings require the `zstd` and `deflate` features
+/// respectively.
+///
+/// The `Accept-Encoding` header is negotiated properly, including `q` weights: a coding with
+/// `q=0` (for example `gzip;q=0`) is never selected, and if the client forbids `identity` (via
+/// `identity;q=0` or a wildcard `*;q=0`) while none of rouille's supported codings are
+/// acceptable either, this function returns a `406 Not Acceptable` response instead of sending
+/// the body uncompressed.
+///
+/// This function uses the default `ContentEncodingConfig`. Use [`apply_with`] to customize
+/// compression levels, skip small bodies, or exclude certain `Content-Type`s.
 ///
 /// # Example
 ///
@@ -36,12 +45,72 @@ use Response;
 ///     content_encoding::apply(request, Response::text("hello world"))
 /// }
 /// ```
-pub fn apply(request: &Request, mut response: Response) -> Response {
+pub fn apply(request: &Request, response: Response) -> Response {
+    apply_with(request, response, &ContentEncodingConfig::default())
+}
+
+/// Configuration used by [`apply_with`] to control how compression is performed.
+///
+/// Build one with `ContentEncodingConfig::default()` and override only the fields that matter
+/// to you.
+///
+/// # Example
+///
+/// ```rust
+/// use rouille::content_encoding::ContentEncodingConfig;
+///
+/// let config = ContentEncodingConfig {
+///     min_body_size: 1024,
+///     excluded_content_types: vec!["image/*".into(), "application/gzip".into()],
+///     .. ContentEncodingConfig::default()
+/// };
+/// ```
+#[derive(Clone, Debug)]
+pub struct ContentEncodingConfig {
+    /// Brotli quality, from `0` (fastest) to `11` (smallest output). Defaults to `6`.
+    pub brotli_quality: u32,
+    /// Gzip compression level. Defaults to `Compression::default()`.
+    pub gzip_level: ::flate2::Compression,
+    /// Deflate (zlib) compression level. Defaults to `Compression::Default`.
+    pub deflate_level: ::deflate::Compression,
+    /// Zstd compression level, or `0` to use zstd's own default. Defaults to `0`.
+    pub zstd_level: i32,
+    /// Bodies smaller than this many bytes are never compressed. Only takes effect when the
+    /// body size is known ahead of time; bodies of unknown size are always considered. Defaults
+    /// to `0`, meaning no minimum.
+    pub min_body_size: usize,
+    /// `Content-Type` values that must never be compressed, even if `response_is_text` would
+    /// otherwise match them. Entries ending in `/*` (e.g. `image/*`) match any subtype. Defaults
+    /// to empty.
+    pub excluded_content_types: Vec<String>,
+}
+
+impl Default for ContentEncodingConfig {
+    fn default() -> ContentEncodingConfig {
+        ContentEncodingConfig {
+            brotli_quality: 6,
+            gzip_level: ::flate2::Compression::default(),
+            deflate_level: ::deflate::Compression::Default,
+            zstd_level: 0,
+            min_body_size: 0,
+            excluded_content_types: Vec::new(),
+        }
+    }
+}
+
+/// Same as [`apply`], but lets you customize compression behavior through a
+/// `ContentEncodingConfig`.
+pub fn apply_with(request: &Request, mut response: Response, config: &ContentEncodingConfig) -> Response {
     // Only text should be encoded. Otherwise just return.
     if !response_is_text(&response) {
         return response;
     }
 
+    // Respect the caller's exclusion list, even for types that look textual.
+    if is_excluded_content_type(&response, &config.excluded_content_types) {
+        return response;
+    }
+
     // If any of the response's headers is equal to `Content-Encoding`, ignore the function
     // call and return immediately.
     if response.headers.iter().any(|&(ref key, _)| key.eq_ignore_ascii_case("Content-Encoding")) {
@@ -49,19 +118,179 @@ pub fn apply(request: &Request, mut response: Response) -> Response {
     }
 
     // Now let's get the list of content encodings accepted by the request.
-    // The list should be ordered from the most desired to the least desired.
-    let encoding_preference = ["br", "gzip", "x-gzip", "identity"];
+    // The list should be ordered from the most desired to the least desired, and only contain
+    // codecs that were actually compiled in: a coding behind a disabled feature must never be
+    // negotiated as if it were supported, or we'd dispatch to its no-op stub and silently send
+    // an uncompressed body.
+    let mut encoding_preference = Vec::new();
+    if cfg!(feature = "brotli") {
+        encoding_preference.push("br");
+    }
+    if cfg!(feature = "zstd") {
+        encoding_preference.push("zstd");
+    }
+    if cfg!(feature = "gzip") {
+        encoding_preference.push("gzip");
+        encoding_preference.push("x-gzip");
+    }
+    if cfg!(feature = "deflate") {
+        encoding_preference.push("deflate");
+    }
+
     let accept_encoding_header = request.header("Accept-Encoding").unwrap_or("");
-    if let Some(preferred_index) = input::priority_header_preferred(&accept_encoding_header, encoding_preference.iter().cloned()) {
-        match encoding_preference[preferred_index] {
-            "br" => brotli(&mut response),
-            "gzip" | "x-gzip" => gzip(&mut response),
-            _ => (),
-        }
+    let negotiation = negotiate_encoding(&accept_encoding_header, &encoding_preference);
+
+    // The size threshold only decides whether it's worth actually running a codec; it must not
+    // skip negotiation itself, since a client that forbids `identity` still needs a 406 rather
+    // than a silently uncompressed small body.
+    let under_size_threshold = is_under_size_threshold(&response, config.min_body_size);
+
+    match negotiation {
+        Negotiation::Coding("br") if !under_size_threshold => brotli(&mut response, config.brotli_quality),
+        Negotiation::Coding("zstd") if !under_size_threshold => zstd(&mut response, config.zstd_level),
+        Negotiation::Coding("gzip") | Negotiation::Coding("x-gzip") if !under_size_threshold => gzip(&mut response, config.gzip_level),
+        Negotiation::Coding("deflate") if !under_size_threshold => deflate(&mut response, config.deflate_level),
+        Negotiation::Coding(_) | Negotiation::Identity => (),
+        Negotiation::NotAcceptable => return Response::text("Not Acceptable").with_status_code(406),
     }
     return response;
 }
 
+// Returns true if the response's body is known to be smaller than `min_size`. A body of unknown
+// size is never skipped, since we can't tell whether it's actually small.
+fn is_under_size_threshold(response: &Response, min_size: usize) -> bool {
+    match response.data.exact_data_length() {
+        Some(size) => size < min_size,
+        None => false,
+    }
+}
+
+// Returns true if the response's Content-Type matches one of the excluded patterns.
+// A pattern ending in `/*` matches any subtype of that top-level type.
+fn is_excluded_content_type(response: &Response, excluded: &[String]) -> bool {
+    response.headers.iter().any(|&(ref key, ref value)| {
+        if !key.eq_ignore_ascii_case("Content-Type") {
+            return false;
+        }
+
+        let content_type = value.to_lowercase();
+        let content_type = content_type.split(';').next().unwrap_or(&content_type).trim();
+
+        excluded.iter().any(|pattern| {
+            let pattern = pattern.to_lowercase();
+            if pattern.ends_with("/*") {
+                content_type.starts_with(&pattern[..pattern.len() - 1])
+            } else {
+                content_type == pattern
+            }
+        })
+    })
+}
+
+/// The outcome of negotiating a `Content-Encoding` against a request's `Accept-Encoding` header.
+pub(crate) enum Negotiation<'a> {
+    /// Use this coding, picked from the `supported` slice passed to `negotiate_encoding`.
+    Coding(&'a str),
+    /// None of the supported codings were preferred; send the body as-is.
+    Identity,
+    /// The client forbade `identity` and none of the supported codings are acceptable either.
+    /// Callers should respond with `406 Not Acceptable`.
+    NotAcceptable,
+}
+
+/// A single coding and its `q` weight, as parsed out of an `Accept-Encoding` header.
+struct AcceptedCoding<'a> {
+    name: &'a str,
+    quality: f32,
+}
+
+/// Splits an `Accept-Encoding` header into its individual codings and `q` weights.
+///
+/// An entry with no `;q=` parameter defaults to a weight of `1.0`, and an unparsable `q` value
+/// is treated the same way rather than discarding the entry entirely.
+fn parse_accept_encoding(header: &str) -> Vec<AcceptedCoding> {
+    header.split(',').filter_map(|entry| {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            return None;
+        }
+
+        let mut parts = entry.splitn(2, ';');
+        let name = parts.next().unwrap().trim();
+        let quality = parts.next().map_or(1.0, |param| {
+            let param = param.trim();
+            if param.starts_with("q=") {
+                param[2..].trim().parse().unwrap_or(1.0)
+            } else {
+                1.0
+            }
+        });
+
+        Some(AcceptedCoding { name: name, quality: quality })
+    }).collect()
+}
+
+/// Negotiates which of the server's `supported` codings (in server-preference order, most
+/// preferred first) to use for a request, based on its `Accept-Encoding` header.
+///
+/// Respects `q` weights, including `q=0` which forbids a coding. A coding in `supported` is only
+/// a candidate if the client named it explicitly or covered it with a `*` wildcard; unlike
+/// `identity`, an unmentioned compressed coding is never assumed acceptable. Among the
+/// candidates, the one with the highest weight wins; ties are broken by server preference order.
+/// If none of `supported` is acceptable, falls back to `identity`, unless `identity` itself has
+/// been forbidden (explicitly, or through a `*;q=0` wildcard), in which case
+/// `Negotiation::NotAcceptable` is returned.
+///
+/// Crate-internal helper, reused by `assets::match_assets` to pick a pre-compressed sidecar.
+pub(crate) fn negotiate_encoding<'a>(accept_encoding: &str, supported: &[&'a str]) -> Negotiation<'a> {
+    if accept_encoding.trim().is_empty() {
+        return Negotiation::Identity;
+    }
+
+    let accepted = parse_accept_encoding(accept_encoding);
+
+    let weight_of = |coding: &str| -> Option<f32> {
+        let mut wildcard = None;
+        for entry in &accepted {
+            if entry.name.eq_ignore_ascii_case(coding) {
+                return Some(entry.quality);
+            }
+            if entry.name == "*" {
+                wildcard = Some(entry.quality);
+            }
+        }
+        wildcard
+    };
+
+    let mut best: Option<(&str, f32)> = None;
+    for &coding in supported {
+        if coding == "identity" {
+            continue;
+        }
+
+        match weight_of(coding) {
+            Some(quality) if quality <= 0.0 => continue,
+            Some(quality) => {
+                if best.map_or(true, |(_, best_quality)| quality > best_quality) {
+                    best = Some((coding, quality));
+                }
+            }
+            // Unlike `identity`, a compressed coding is never acceptable unless the client
+            // named it explicitly or covered it with a `*` wildcard.
+            None => continue,
+        }
+    }
+
+    if let Some((coding, _)) = best {
+        return Negotiation::Coding(coding);
+    }
+
+    match weight_of("identity") {
+        Some(quality) if quality <= 0.0 => Negotiation::NotAcceptable,
+        _ => Negotiation::Identity,
+    }
+}
+
 // Returns true if the Content-Type of the response is a type that should be encoded.
 // Since encoding is purely an optimisation, it's not a problem if the function sometimes has
 // false positives or false negatives.
@@ -81,30 +310,23 @@ fn response_is_text(response: &Response) -> bool {
 }
 
 #[cfg(feature = "gzip")]
-fn gzip(response: &mut Response) {
+fn gzip(response: &mut Response, level: ::flate2::Compression) {
     use ResponseBody;
     use std::mem;
-    use std::io;
-    use deflate::deflate_bytes_gzip;
+    use flate2::read::GzEncoder;
 
     response.headers.push(("Content-Encoding".into(), "gzip".into()));
     let previous_body = mem::replace(&mut response.data, ResponseBody::empty());
-    let (mut raw_data, size) = previous_body.into_reader_and_size();
-    let mut src = match size {
-        Some(size) => Vec::with_capacity(size),
-        None => Vec::new(),
-    };
-    io::copy(&mut raw_data, &mut src).expect("Failed reading response body while gzipping");
-    let zipped = deflate_bytes_gzip(&src);
-    response.data = ResponseBody::from_data(zipped);
+    let (raw_data, _) = previous_body.into_reader_and_size();
+    response.data = ResponseBody::from_reader(GzEncoder::new(raw_data, level));
 }
 
 #[cfg(not(feature = "gzip"))]
 #[inline]
-fn gzip(response: &mut Response) {}
+fn gzip(response: &mut Response, _level: ::flate2::Compression) {}
 
 #[cfg(feature = "brotli")]
-fn brotli(response: &mut Response) {
+fn brotli(response: &mut Response, quality: u32) {
     use ResponseBody;
     use std::mem;
     use brotli2::read::BrotliEncoder;
@@ -112,15 +334,183 @@ fn brotli(response: &mut Response) {
     response.headers.push(("Content-Encoding".into(), "br".into()));
     let previous_body = mem::replace(&mut response.data, ResponseBody::empty());
     let (raw_data, _) = previous_body.into_reader_and_size();
-    response.data = ResponseBody::from_reader(BrotliEncoder::new(raw_data, 6));
+    response.data = ResponseBody::from_reader(BrotliEncoder::new(raw_data, quality));
 }
 
 #[cfg(not(feature = "brotli"))]
 #[inline]
-fn brotli(response: &mut Response) {}
+fn brotli(response: &mut Response, _quality: u32) {}
+
+#[cfg(feature = "zstd")]
+fn zstd(response: &mut Response, level: i32) {
+    use ResponseBody;
+    use std::mem;
+    use zstd::stream::read::Encoder;
+
+    response.headers.push(("Content-Encoding".into(), "zstd".into()));
+    let previous_body = mem::replace(&mut response.data, ResponseBody::empty());
+    let (raw_data, _) = previous_body.into_reader_and_size();
+    response.data = ResponseBody::from_reader(Encoder::new(raw_data, clamp_zstd_level(level)).unwrap());
+}
+
+// Zstd only accepts compression levels `1..=22` (`0` means "use the library's own default").
+// Clamp instead of passing an operator-configured level straight through, so a typo in
+// `ContentEncodingConfig::zstd_level` (e.g. `99`) can't make every compressed response panic.
+#[cfg(feature = "zstd")]
+fn clamp_zstd_level(level: i32) -> i32 {
+    if level == 0 {
+        0
+    } else {
+        level.max(1).min(22)
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+#[inline]
+fn zstd(response: &mut Response, _level: i32) {}
+
+#[cfg(feature = "deflate")]
+fn deflate(response: &mut Response, level: ::deflate::Compression) {
+    use ResponseBody;
+    use std::mem;
+    use std::io;
+    use deflate::deflate_bytes_zlib_conf;
+
+    response.headers.push(("Content-Encoding".into(), "deflate".into()));
+    let previous_body = mem::replace(&mut response.data, ResponseBody::empty());
+    let (mut raw_data, size) = previous_body.into_reader_and_size();
+    let mut src = match size {
+        Some(size) => Vec::with_capacity(size),
+        None => Vec::new(),
+    };
+    io::copy(&mut raw_data, &mut src).expect("Failed reading response body while deflating");
+    let deflated = deflate_bytes_zlib_conf(&src, level);
+    response.data = ResponseBody::from_data(deflated);
+}
+
+#[cfg(not(feature = "deflate"))]
+#[inline]
+fn deflate(response: &mut Response, _level: ::deflate::Compression) {}
 
 #[cfg(test)]
 mod tests {
+    use super::{is_excluded_content_type, is_under_size_threshold, negotiate_encoding, Negotiation};
+
+    fn assert_coding(accept_encoding: &str, supported: &[&str], expected: &str) {
+        match negotiate_encoding(accept_encoding, supported) {
+            Negotiation::Coding(coding) => assert_eq!(coding, expected),
+            other => panic!("expected Coding({:?}), got {:?}", expected, debug_name(&other)),
+        }
+    }
+
+    fn debug_name(negotiation: &Negotiation) -> &'static str {
+        match *negotiation {
+            Negotiation::Coding(_) => "Coding",
+            Negotiation::Identity => "Identity",
+            Negotiation::NotAcceptable => "NotAcceptable",
+        }
+    }
 
-    // TODO: more tests for encoding stuff
+    #[test]
+    fn picks_the_only_explicitly_named_coding() {
+        // A coding that isn't named (or wildcarded) must never be picked over one that is.
+        assert_coding("gzip", &["br", "zstd", "gzip", "x-gzip", "deflate"], "gzip");
+    }
+
+    #[test]
+    fn q_zero_forbids_a_coding() {
+        assert_coding("gzip;q=0, br", &["br", "gzip"], "br");
+    }
+
+    #[test]
+    fn highest_explicit_weight_wins() {
+        assert_coding("br;q=0.1, gzip;q=1.0", &["br", "gzip"], "gzip");
+    }
+
+    #[test]
+    fn ties_are_broken_by_server_preference_order() {
+        assert_coding("br, gzip", &["gzip", "br"], "gzip");
+    }
+
+    #[test]
+    fn wildcard_covers_unmentioned_codings() {
+        assert_coding("*", &["br", "gzip"], "br");
+    }
+
+    #[test]
+    fn empty_header_means_identity() {
+        match negotiate_encoding("", &["br", "gzip"]) {
+            Negotiation::Identity => (),
+            other => panic!("expected Identity, got {:?}", debug_name(&other)),
+        }
+    }
+
+    #[test]
+    fn no_supported_coding_acceptable_falls_back_to_identity() {
+        match negotiate_encoding("gzip;q=0", &["gzip"]) {
+            Negotiation::Identity => (),
+            other => panic!("expected Identity, got {:?}", debug_name(&other)),
+        }
+    }
+
+    #[test]
+    fn identity_forbidden_with_no_alternative_is_not_acceptable() {
+        match negotiate_encoding("identity;q=0, gzip;q=0", &["gzip"]) {
+            Negotiation::NotAcceptable => (),
+            other => panic!("expected NotAcceptable, got {:?}", debug_name(&other)),
+        }
+    }
+
+    #[test]
+    fn wildcard_q_zero_forbids_identity_too() {
+        match negotiate_encoding("*;q=0", &["gzip"]) {
+            Negotiation::NotAcceptable => (),
+            other => panic!("expected NotAcceptable, got {:?}", debug_name(&other)),
+        }
+    }
+
+    #[test]
+    fn excludes_content_type_by_exact_match() {
+        let excluded = vec!["application/gzip".to_string()];
+
+        let mut response = ::Response::text("");
+        response.headers.push(("Content-Type".into(), "application/gzip".into()));
+        assert!(is_excluded_content_type(&response, &excluded));
+
+        let mut other = ::Response::text("");
+        other.headers.push(("Content-Type".into(), "text/plain".into()));
+        assert!(!is_excluded_content_type(&other, &excluded));
+    }
+
+    #[test]
+    fn excludes_content_type_by_wildcard_subtype() {
+        let excluded = vec!["image/*".to_string()];
+
+        let mut response = ::Response::text("");
+        response.headers.push(("Content-Type".into(), "image/png; charset=binary".into()));
+        assert!(is_excluded_content_type(&response, &excluded));
+    }
+
+    #[test]
+    fn small_known_size_body_is_under_threshold() {
+        let response = ::Response::text("hi");
+        assert!(is_under_size_threshold(&response, 1024));
+    }
+
+    #[test]
+    fn large_known_size_body_is_not_under_threshold() {
+        let response = ::Response::text("x".repeat(2048));
+        assert!(!is_under_size_threshold(&response, 1024));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_level_is_clamped_into_the_valid_range() {
+        use super::clamp_zstd_level;
+
+        assert_eq!(clamp_zstd_level(0), 0);
+        assert_eq!(clamp_zstd_level(99), 22);
+        assert_eq!(clamp_zstd_level(-5), 1);
+        assert_eq!(clamp_zstd_level(10), 10);
+    }
 }
@@ -15,6 +15,7 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use threadpool::ThreadPool;
 
+pub mod content_encoding;
 pub mod input;
 
 mod assets;